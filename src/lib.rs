@@ -6,10 +6,32 @@ use nvim_oxi::conversion::{self, FromObject, ToObject};
 use nvim_oxi::serde::{Deserializer, Serializer};
 use nvim_oxi::{api, lua, print, Dictionary, Function, Object};
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use thiserror::Error;
 
+mod services;
+
+use services::{build_url, provider_for_kind, GitRef, LineRange, Registry};
+
+thread_local! {
+    /// Providers consulted when formatting a link. Seeded with the built-ins
+    /// and extended from the user's `setup` call.
+    static REGISTRY: RefCell<Registry> = RefCell::new(Registry::new());
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Config {
+    #[serde(default)]
+    providers: Vec<ProviderConfig>,
+}
+
+/// A user-declared host mapped onto a built-in formatter, e.g.
+/// `{ host = "git.corp.internal", kind = "github" }`.
 #[derive(Serialize, Deserialize)]
-struct Config {}
+struct ProviderConfig {
+    host: String,
+    kind: String,
+}
 
 impl FromObject for Config {
     fn from_object(obj: Object) -> Result<Self, conversion::Error> {
@@ -74,82 +96,6 @@ enum PluginError {
     UnsupportedGitService(String),
 }
 
-#[derive(Copy, Clone)]
-enum GitService {
-    GitHub,
-    SourceHut,
-}
-
-impl GitService {
-    fn new(url: &GitUrl) -> Result<Self, PluginError> {
-        if url.owner.is_none() {
-            return Err(PluginError::MissingRepositoryOwner);
-        }
-        match url.host.as_ref().map(|s| s.as_str()) {
-            Some("github.com") => Ok(Self::GitHub),
-            Some("git.sr.ht") => Ok(Self::SourceHut),
-            Some(s) => Err(PluginError::UnsupportedGitService(s.to_string())),
-            None => Err(PluginError::MissingGitService),
-        }
-    }
-}
-
-struct LineRange(usize, usize);
-
-struct GitServiceUrl {
-    service: GitService,
-    url: GitUrl,
-    obj: String,
-    path: String,
-    range: Option<LineRange>,
-}
-
-impl GitServiceUrl {
-    fn new(
-        url: GitUrl,
-        obj: String,
-        path: String,
-        range: Option<LineRange>,
-    ) -> Result<Self, PluginError> {
-        Ok(Self {
-            service: GitService::new(&url)?,
-            url,
-            obj,
-            path,
-            range,
-        })
-    }
-}
-
-impl std::fmt::Display for GitServiceUrl {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        let path = match (self.service, &self.obj) {
-            // https://github.com/<owner>/<project>/blob/<obj>/<path>
-            (GitService::GitHub, obj) => format!("blob/{}/{}", obj, self.path),
-            // https://git.sr.ht/<owner>/<project>/tree/<obj>/item/<path>
-            (GitService::SourceHut, obj) => format!("tree/{}/item/{}", obj, self.path),
-        };
-
-        let range = match (self.service, self.range.as_ref()) {
-            (_, None) => format!(""),
-            // SourceHut does not have multiline highlighting at the time of writing.
-            (GitService::SourceHut, Some(LineRange(a, _))) => format!("#L{a}"),
-            (_, Some(LineRange(a, b))) if a == b => format!("#L{a}"),
-            (_, Some(LineRange(a, b))) => format!("#L{a}-L{b}"),
-        };
-
-        write!(
-            f,
-            "https://{}/{}/{}/{}{}",
-            self.url.host.as_ref().unwrap(),
-            self.url.owner.as_ref().unwrap(),
-            project_name(&self.url),
-            path,
-            range
-        )
-    }
-}
-
 #[nvim_oxi::plugin]
 fn nvim_repolink() -> Result<Dictionary, PluginError> {
     let opts = CreateCommandOpts::builder()
@@ -171,21 +117,68 @@ fn nvim_repolink() -> Result<Dictionary, PluginError> {
     // This will allow Lazy to call `require(...).setup({})`, so that we won't have to ask the user
     // to manually call `require` or using `config = ...` in Lazy. Lazy dissuades the use of
     // `config`. See https://lazy.folke.io/spec.
-    Ok(Dictionary::from_iter([(
-        "setup",
-        Object::from(Function::from_fn(|_: Config| {})),
-    )]))
+    Ok(Dictionary::from_iter([
+        (
+            "setup",
+            Object::from(Function::from_fn(|config: Config| {
+                REGISTRY.with(|registry| {
+                    let mut registry = registry.borrow_mut();
+                    for provider in config.providers {
+                        if let Some(provider) = provider_for_kind(&provider.kind, provider.host) {
+                            registry.register(provider);
+                        }
+                    }
+                });
+            })),
+        ),
+        (
+            "get_url",
+            // Return the link instead of printing it, so Lua callers can yank
+            // it, copy it to the clipboard or hand it to `xdg-open`. Accepts an
+            // optional remote name and an optional `line1`/`line2` range.
+            Object::from(Function::from_fn(
+                |(remote, line1, line2): (Option<String>, Option<usize>, Option<usize>)| {
+                    let range = match (line1, line2) {
+                        (Some(line1), Some(line2)) => Some(LineRange(line1, line2)),
+                        (Some(line), None) | (None, Some(line)) => Some(LineRange(line, line)),
+                        (None, None) => None,
+                    };
+                    repolink_url(remote.as_deref(), range, false)
+                },
+            )),
+        ),
+    ]))
 }
 
 fn generate_repolink(args: CommandArgs) -> Result<(), PluginError> {
+    let range = if args.range == 0 {
+        None
+    } else {
+        Some(LineRange(args.line1, args.line2))
+    };
+
+    let link = repolink_url(args.args.as_deref(), range, args.bang)?;
+    print!("{link}");
+
+    Ok(())
+}
+
+/// Build the link for the current buffer against `remote_name` (defaulting to
+/// `origin`). This is the shared core behind both the `:Repolink` command and
+/// the Lua-callable `get_url`.
+fn repolink_url(
+    remote_name: Option<&str>,
+    range: Option<LineRange>,
+    permalink: bool,
+) -> Result<String, PluginError> {
     let repo = Repository::discover(std::env::current_dir()?)?;
-    let remote_name = args.args.unwrap_or("origin".to_string());
-    let remote = repo.find_remote(&remote_name)?;
-    let url = GitUrl::parse(
-        std::str::from_utf8(remote.url_bytes()).map_err(|_| PluginError::Utf8("remote URL"))?,
-    )?;
+    let remote_name = remote_name.unwrap_or("origin");
+    let remote = repo.find_remote(remote_name)?;
+    let raw = std::str::from_utf8(remote.url_bytes()).map_err(|_| PluginError::Utf8("remote URL"))?;
+    let rewritten = rewrite_with_insteadof(&repo, raw);
+    let url = GitUrl::parse(&canonicalize_remote_url(&rewritten))?;
 
-    let head_obj = figure_out_git_head(&repo, &remote_name)?;
+    let head_obj = figure_out_git_head(&repo, remote_name, permalink)?;
 
     let repo_path = repo.workdir().ok_or(PluginError::BareRepository)?;
     let file_path = api::get_current_buf().get_name()?;
@@ -196,45 +189,153 @@ fn generate_repolink(args: CommandArgs) -> Result<(), PluginError> {
         .into_string()
         .unwrap_or_else(|s| s.as_os_str().to_string_lossy().to_string());
 
-    let range = if args.range == 0 {
-        None
-    } else {
-        Some(LineRange(args.line1, args.line2))
+    if url.owner.is_none() {
+        return Err(PluginError::MissingRepositoryOwner);
+    }
+    let host = url.host.as_ref().ok_or(PluginError::MissingGitService)?;
+    let owner = url.owner.as_ref().unwrap();
+    let project = project_name(&url);
+
+    REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        match registry.provider_for(host) {
+            Some(provider) => Ok(build_url(
+                provider,
+                host,
+                owner,
+                &project,
+                &head_obj,
+                &rel_path,
+                range.as_ref(),
+            )),
+            None => Err(PluginError::UnsupportedGitService(host.clone())),
+        }
+    })
+}
+
+/// Apply the repository's `url.<base>.insteadOf` / `pushInsteadOf` rewrites to
+/// the raw remote string, using the longest matching prefix (the same rule Git
+/// itself follows). Returns the string unchanged when nothing matches.
+fn rewrite_with_insteadof(repo: &Repository, remote: &str) -> String {
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(_) => return remote.to_string(),
     };
 
-    print!("{}", GitServiceUrl::new(url, head_obj, rel_path, range)?);
+    let mut best: Option<(usize, String)> = None;
+    for glob in ["url.*.insteadof", "url.*.pushinsteadof"] {
+        let mut entries = match config.entries(Some(glob)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        while let Some(entry) = entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            let name = match entry.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let prefix = match entry.value() {
+                Some(value) => value,
+                None => continue,
+            };
+            // Git lowercases the section and variable but preserves the
+            // subsection, so the base is whatever sits between the two.
+            let base = name.strip_prefix("url.").and_then(|s| {
+                s.strip_suffix(".insteadof")
+                    .or_else(|| s.strip_suffix(".pushinsteadof"))
+            });
+            let base = match base {
+                Some(base) => base,
+                None => continue,
+            };
+
+            let longer = match &best {
+                Some((len, _)) => prefix.len() > *len,
+                None => true,
+            };
+            if remote.starts_with(prefix) && longer {
+                best = Some((prefix.len(), format!("{base}{}", &remote[prefix.len()..])));
+            }
+        }
+    }
 
-    Ok(())
+    best.map(|(_, rewritten)| rewritten)
+        .unwrap_or_else(|| remote.to_string())
 }
 
-fn figure_out_git_head(repo: &Repository, remote_name: &str) -> Result<String, PluginError> {
+/// Normalize `ssh://`, `git://`, `git+https://` and scp-style
+/// (`git@host:owner/repo`) remotes down to a plain `https` host/owner/project
+/// form so host detection in the provider registry is scheme-agnostic.
+fn canonicalize_remote_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    let url = url.strip_prefix("git+").unwrap_or(url);
+
+    for scheme in ["ssh://", "git://"] {
+        if let Some(rest) = url.strip_prefix(scheme) {
+            return format!("https://{}", strip_userinfo(rest));
+        }
+    }
+
+    if url.contains("://") {
+        return url.to_string();
+    }
+
+    // scp-like syntax: [user@]host:owner/repo
+    if let Some((head, path)) = url.split_once(':') {
+        return format!("https://{}/{}", strip_userinfo(head), path);
+    }
+
+    url.to_string()
+}
+
+fn strip_userinfo(authority: &str) -> &str {
+    match authority.split_once('@') {
+        Some((_, rest)) => rest,
+        None => authority,
+    }
+}
+
+fn figure_out_git_head(
+    repo: &Repository,
+    remote_name: &str,
+    permalink: bool,
+) -> Result<GitRef, PluginError> {
     let head = repo.head()?;
 
     if head.is_note() || head.is_tag() || head.is_remote() {
         return Err(PluginError::InvalidHeadType);
     }
 
-    let head_obj = if repo.head_detached()? {
-        search_references(&repo, |r| {
+    // A permalink (`:Repolink!`) always pins to the immutable commit object so
+    // the link keeps pointing at the same lines once the branch moves on.
+    if permalink {
+        return Ok(GitRef::Commit(head.peel_to_commit()?.id().to_string()));
+    }
+
+    if repo.head_detached()? {
+        let tag = search_references(&repo, |r| {
             if !r.is_tag() {
                 return None;
             }
             std::str::from_utf8(r.shorthand_bytes())
                 .ok()
                 .map(|s| s.to_string())
-        })?
-        .or_else(|| {
-            head.peel_to_commit()
-                .ok()
-                .map(|commit| commit.id().to_string())
-        })
+        })?;
+
+        if let Some(tag) = tag {
+            return Ok(GitRef::Tag(tag));
+        }
+
+        Ok(GitRef::Commit(head.peel_to_commit()?.id().to_string()))
     } else if head.is_branch() {
-        get_remote_branch(&repo, remote_name)?
+        let branch = get_remote_branch(&repo, remote_name)?.ok_or(PluginError::InvalidHeadType)?;
+        Ok(GitRef::Named(branch))
     } else {
-        None
-    };
-
-    head_obj.ok_or(PluginError::InvalidHeadType)
+        Err(PluginError::InvalidHeadType)
+    }
 }
 
 fn get_remote_branch(
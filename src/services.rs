@@ -1,138 +1,354 @@
-pub enum GitService {
-    GitHub,
-    SourceHut,
+//! Git hosting providers and the runtime registry used to turn a remote's
+//! host into a concrete blob-URL formatter.
+//!
+//! Host detection is kept separate from URL formatting: a provider decides
+//! whether it owns a host via [`GitHostingProvider::host_matches`], and the
+//! formatting methods only ever see the pieces they need. This lets an
+//! enterprise or self-hosted instance reuse a built-in formatter under a
+//! different host (see [`provider_for_kind`] and [`Registry::register`]).
+
+/// A resolved Git object the link points at: either a symbolic name (branch
+/// or tag) or a full commit SHA. Providers that grammatically distinguish the
+/// two (e.g. Gitea's `src/branch` vs `src/commit`) match on the variant.
+pub enum GitRef {
+    Named(String),
+    Tag(String),
+    Commit(String),
 }
 
-pub struct LineRange(pub usize, pub usize);
+impl GitRef {
+    fn as_str(&self) -> &str {
+        match self {
+            GitRef::Named(s) | GitRef::Tag(s) | GitRef::Commit(s) => s,
+        }
+    }
 
-impl LineRange {
-    fn linerange_for(&self, gs: &GitService) -> String {
-        match (gs, self.0, self.1)  {
-            (GitService::GitHub, a, b) if a == b => format!("#L{a}"),
-            (GitService::GitHub, a, b) => format!("#L{a}-{b}"),
-            /* SourceHut does not have multiline select at the time of writing. */
-            (GitService::SourceHut, a, _) => format!("#L{a}"),
+    /// The same ref with its name percent-encoded (a branch may carry spaces
+    /// or other bytes that would otherwise break the URL), keeping `/` so a
+    /// `feature/x` branch stays a path.
+    fn encoded(&self) -> GitRef {
+        match self {
+            GitRef::Named(s) => GitRef::Named(encode_path(s)),
+            GitRef::Tag(s) => GitRef::Tag(encode_path(s)),
+            GitRef::Commit(s) => GitRef::Commit(encode_path(s)),
         }
     }
 }
 
-impl std::fmt::Display for LineRange {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match (self.0, self.1) {
-            (begin, end) if begin == end => write!(f, "#L{begin}"),
-            (begin, end) => write!(f, "#L{begin}-L{end}"),
+/// Percent-encode a single URL path segment, leaving the unreserved set
+/// (`A-Z a-z 0-9 - . _ ~`) intact and encoding every other byte — spaces,
+/// `#`, `?`, `%`, control and non-ASCII bytes — the way a forge decodes a
+/// path component back into a repository path.
+fn encode_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for &byte in segment.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char);
+            }
+            _ => {
+                out.push('%');
+                out.push(hex(byte >> 4));
+                out.push(hex(byte & 0xf));
+            }
         }
     }
+    out
+}
+
+/// Percent-encode each segment of `path`, leaving `/` as the separator.
+fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(encode_segment)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn hex(nibble: u8) -> char {
+    match nibble {
+        0..=9 => (b'0' + nibble) as char,
+        _ => (b'A' + (nibble - 10)) as char,
+    }
+}
+
+pub struct LineRange(pub usize, pub usize);
+
+/// A forge whose web frontend can render a blob at a given ref and line range.
+pub trait GitHostingProvider {
+    /// Whether this provider should format links for `host`.
+    fn host_matches(&self, host: &str) -> bool;
+
+    /// The path that follows `https://{host}/{owner}/{project}/` and points at
+    /// `path` as of `obj`.
+    fn blob_path(&self, obj: &GitRef, path: &str) -> String;
+
+    /// The URL fragment that highlights `range` on this forge.
+    fn line_fragment(&self, range: &LineRange) -> String;
+}
+
+/// Assemble the final link from a provider and the remote's coordinates.
+pub fn build_url(
+    provider: &dyn GitHostingProvider,
+    host: &str,
+    owner: &str,
+    project: &str,
+    obj: &GitRef,
+    path: &str,
+    range: Option<&LineRange>,
+) -> String {
+    let obj = obj.encoded();
+    let path = encode_path(path);
+    let mut url = format!(
+        "https://{host}/{owner}/{project}/{}",
+        provider.blob_path(&obj, &path)
+    );
+    if let Some(range) = range {
+        url.push_str(provider.line_fragment(range).as_str());
+    }
+    url
+}
+
+pub struct GitHub {
+    pub host: String,
 }
 
-// this is intended to build upon static strings.
-pub struct Data<'a> {
-    pub project: &'a str,
-    pub owner: &'a str,
-    pub path: &'a str,
-    pub branch_or_tag_name: Option<String>,
-    pub hash: Option<String>,
-    pub line_range: &'a Option<LineRange>,
-    pub service: GitService,
+impl Default for GitHub {
+    fn default() -> Self {
+        Self {
+            host: "github.com".to_string(),
+        }
+    }
 }
 
-pub struct GitHub {}
-impl GitHub {
+impl GitHostingProvider for GitHub {
     /* format examples:
      * https://github.com/pjhades/nvim-repolink/blob/master/src/lib.rs
      * https://github.com/psyomn/music/blob/feature/faim-ost/faim-ost/main-theme.ly
      * https://github.com/psyomn/zig-getopt/blob/v1.0.1-fake/getopt.zig */
-    pub const HOST: &'static str = "github.com";
-    pub fn project_url(d: &Data) -> String {
-        let project = d.project;
-        let owner = d.owner;
-        let host = GitHub::HOST;
-        format!("https://{host}/{owner}/{project}")
+    fn host_matches(&self, host: &str) -> bool {
+        host == self.host
     }
 
-    pub fn service_path(d: &Data) -> String {
-        let path = d.path;
+    fn blob_path(&self, obj: &GitRef, path: &str) -> String {
+        format!("blob/{}/{}", obj.as_str(), path)
+    }
 
-        if let Some(middle) = d.branch_or_tag_name.as_ref() {
-            let mut ret= format!("/blob/{middle}/{path}");
+    fn line_fragment(&self, range: &LineRange) -> String {
+        match (range.0, range.1) {
+            (a, b) if a == b => format!("#L{a}"),
+            (a, b) => format!("#L{a}-L{b}"),
+        }
+    }
+}
 
-            if let Some(range) = d.line_range.as_ref() {
-                ret.push_str(range.linerange_for(&d.service).as_str());
-            }
+pub struct SourceHut {
+    pub host: String,
+}
+
+impl Default for SourceHut {
+    fn default() -> Self {
+        Self {
+            host: "git.sr.ht".to_string(),
+        }
+    }
+}
+
+impl GitHostingProvider for SourceHut {
+    /* format examples:
+     * https://git.sr.ht/~psyomn/zig-postcard/tree/master/item/src/post.zig
+     * https://git.sr.ht/~psyomn/ecophagy/tree/feature/planner/item/planner/server.go#L15
+     * https://git.sr.ht/~psyomn/oui-zig/tree/1.0.0/item/src/main.zig */
+    fn host_matches(&self, host: &str) -> bool {
+        host == self.host
+    }
+
+    fn blob_path(&self, obj: &GitRef, path: &str) -> String {
+        format!("tree/{}/item/{}", obj.as_str(), path)
+    }
+
+    fn line_fragment(&self, range: &LineRange) -> String {
+        /* SourceHut does not have multiline highlighting at the time of writing. */
+        format!("#L{}", range.0)
+    }
+}
 
-            return ret;
+pub struct GitLab {
+    pub host: String,
+}
+
+impl Default for GitLab {
+    fn default() -> Self {
+        Self {
+            host: "gitlab.com".to_string(),
         }
+    }
+}
 
-        if let Some(hash) = d.hash.as_ref() {
-            return format!("/commit/{hash}");
+impl GitHostingProvider for GitLab {
+    /* format examples:
+     * https://gitlab.com/gitlab-org/gitlab/-/blob/master/README.md#L12-20
+     * https://gitlab.com/gitlab-org/gitlab/-/blob/v16.0.0/README.md */
+    fn host_matches(&self, host: &str) -> bool {
+        host == self.host
+    }
+
+    fn blob_path(&self, obj: &GitRef, path: &str) -> String {
+        format!("-/blob/{}/{}", obj.as_str(), path)
+    }
+
+    fn line_fragment(&self, range: &LineRange) -> String {
+        match (range.0, range.1) {
+            (a, b) if a == b => format!("#L{a}"),
+            (a, b) => format!("#L{a}-{b}"),
         }
+    }
+}
 
-        // TODO: this might not be the way to do things.
-        panic!("unreachable");
+pub struct Gitea {
+    pub host: String,
+}
+
+impl Default for Gitea {
+    fn default() -> Self {
+        /* Gitea/Forgejo have no canonical public host; Codeberg is the
+         * best-known instance, so that is what we claim out of the box. */
+        Self {
+            host: "codeberg.org".to_string(),
+        }
     }
 }
 
-struct SourceHut {}
-impl SourceHut {
+impl GitHostingProvider for Gitea {
     /* format examples:
-     * [base-url][owner][project]/tree/[branch or tag]/item/[path]
-     *      https://git.sr.ht/~psyomn/zig-postcard/tree/master/item/src/post.zig
-     *      https://git.sr.ht/~psyomn/zig-postcard/commit/535309acbc07a8f745b6c1c91b87cff220913149
-     *      https://git.sr.ht/~psyomn/ecophagy/tree/feature/planner/item/planner/errors.go
-     *      https://git.sr.ht/~psyomn/ecophagy/tree/feature/planner/item/planner/server.go#L15
-     *      https://git.sr.ht/~psyomn/oui-zig/tree/1.0.0/item/src/main.zig#L16
-     *      https://git.sr.ht/~psyomn/oui-zig/tree/1.0.0/item/src/main.zig */
-    const HOST: &'static str = "git.sr.ht";
-
-    pub fn project_url(d: &Data) -> String {
-        let project = d.project;
-        let owner = d.owner;
-        let host = SourceHut::HOST;
-        /* note: sourcehut has ~user for the owner field.  This information is codified in the
-         * .git/config file */
-        format!("https://{host}/{owner}/{project}")
-    }
-
-    pub fn service_path(d: &Data) -> String {
-        let path = d.path;
-
-        if let Some(middle) = d.branch_or_tag_name.as_ref() {
-            let mut ret= format!("/tree/{middle}/item/{path}");
-
-            if let Some(range) = d.line_range.as_ref() {
-                ret.push_str(range.linerange_for(&d.service).as_str());
-            }
+     * https://codeberg.org/forgejo/forgejo/src/branch/forgejo/README.md#L12-L20
+     * https://codeberg.org/forgejo/forgejo/src/commit/535309a/README.md */
+    fn host_matches(&self, host: &str) -> bool {
+        host == self.host
+    }
 
-            return ret;
+    fn blob_path(&self, obj: &GitRef, path: &str) -> String {
+        // Gitea routes `/src/branch/` through a branch-only lookup, so a tag
+        // must use its own `/src/tag/` slot rather than ride along as a branch.
+        match obj {
+            GitRef::Named(name) => format!("src/branch/{name}/{path}"),
+            GitRef::Tag(name) => format!("src/tag/{name}/{path}"),
+            GitRef::Commit(sha) => format!("src/commit/{sha}/{path}"),
         }
+    }
+
+    fn line_fragment(&self, range: &LineRange) -> String {
+        match (range.0, range.1) {
+            (a, b) if a == b => format!("#L{a}"),
+            (a, b) => format!("#L{a}-L{b}"),
+        }
+    }
+}
 
-        if let Some(hash) = d.hash.as_ref() {
-            return format!("/commit/{hash}");
+pub struct Bitbucket {
+    pub host: String,
+}
+
+impl Default for Bitbucket {
+    fn default() -> Self {
+        Self {
+            host: "bitbucket.org".to_string(),
         }
+    }
+}
 
-        // TODO: this might not be the way to do things.
-        panic!("unreachable");
+impl GitHostingProvider for Bitbucket {
+    /* format examples:
+     * https://bitbucket.org/atlassian/stash/src/master/README.md#lines-12:20
+     * https://bitbucket.org/atlassian/stash/src/535309a/README.md */
+    fn host_matches(&self, host: &str) -> bool {
+        host == self.host
+    }
+
+    fn blob_path(&self, obj: &GitRef, path: &str) -> String {
+        format!("src/{}/{}", obj.as_str(), path)
+    }
+
+    fn line_fragment(&self, range: &LineRange) -> String {
+        match (range.0, range.1) {
+            (a, b) if a == b => format!("#lines-{a}"),
+            (a, b) => format!("#lines-{a}:{b}"),
+        }
     }
 }
 
-pub fn service_for(host: &str) -> Option<GitService> {
-    match host {
-        GitHub::HOST => Some(GitService::GitHub),
-        SourceHut::HOST => Some(GitService::SourceHut),
+/// Construct a built-in provider under an arbitrary `host`, keyed by `kind`.
+/// This is how a `git.corp.internal` GitHub Enterprise instance reuses the
+/// GitHub formatter. Returns `None` for an unknown `kind`.
+pub fn provider_for_kind(kind: &str, host: String) -> Option<Box<dyn GitHostingProvider>> {
+    match kind {
+        "github" => Some(Box::new(GitHub { host })),
+        "sourcehut" => Some(Box::new(SourceHut { host })),
+        "gitlab" => Some(Box::new(GitLab { host })),
+        // Codeberg is a Gitea/Forgejo instance, so they share a formatter.
+        "gitea" | "forgejo" | "codeberg" => Some(Box::new(Gitea { host })),
+        "bitbucket" => Some(Box::new(Bitbucket { host })),
         _ => None,
     }
 }
 
-pub fn project_url_from(d: &Data) -> String {
-    match &d.service {
-        GitService::GitHub => GitHub::project_url(d),
-        GitService::SourceHut => SourceHut::project_url(d),
+/// The set of providers consulted when formatting a link, in precedence order.
+pub struct Registry {
+    providers: Vec<Box<dyn GitHostingProvider>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registry {
+    /// A registry seeded with the forges supported out of the box.
+    pub fn new() -> Self {
+        Self {
+            providers: vec![
+                Box::<GitHub>::default(),
+                Box::<SourceHut>::default(),
+                Box::<GitLab>::default(),
+                Box::<Gitea>::default(),
+                Box::<Bitbucket>::default(),
+            ],
+        }
+    }
+
+    /// Add a provider, giving it precedence over the built-ins so a
+    /// user-configured host can shadow a default.
+    pub fn register(&mut self, provider: Box<dyn GitHostingProvider>) {
+        self.providers.insert(0, provider);
+    }
+
+    /// The first provider that claims `host`, if any.
+    pub fn provider_for(&self, host: &str) -> Option<&dyn GitHostingProvider> {
+        self.providers
+            .iter()
+            .map(AsRef::as_ref)
+            .find(|p| p.host_matches(host))
     }
 }
 
-pub fn service_path_from(d: &Data) -> String {
-    match &d.service {
-        GitService::GitHub => GitHub::service_path(d),
-        GitService::SourceHut => SourceHut::service_path(d),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_spaces_in_ref_and_hash_in_path() {
+        let url = build_url(
+            &GitHub::default(),
+            "github.com",
+            "psyomn",
+            "music",
+            &GitRef::Named("feature/faim ost".to_string()),
+            "faim-ost/main #theme.ly",
+            None,
+        );
+        assert_eq!(
+            url,
+            "https://github.com/psyomn/music/blob/feature/faim%20ost/faim-ost/main%20%23theme.ly"
+        );
     }
 }